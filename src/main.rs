@@ -1,14 +1,52 @@
 use automerge::sync::SyncDoc;
-use automerge::{sync, Value, ROOT};
+use automerge::{sync, ObjId, Value, ROOT};
 use automerge::{transaction::Transactable, AutoCommit, Change, ObjType, ReadDoc};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+mod net;
+mod persistence;
+mod sim;
+
 struct NetworkMessage {
     changes: Vec<Change>,
 }
 
-#[derive(Debug, Clone)]
+impl NetworkMessage {
+    /// Wire format: a `u32` change count, then each change as a
+    /// length-prefixed `Change::raw_bytes()` blob.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend((self.changes.len() as u32).to_be_bytes());
+        for change in &self.changes {
+            let raw = change.raw_bytes();
+            bytes.extend((raw.len() as u32).to_be_bytes());
+            bytes.extend(raw);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, automerge::AutomergeError> {
+        let mut offset = 0;
+        let count = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        offset += 4;
+
+        let mut changes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let len = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            offset += 4;
+            changes.push(Change::from_bytes(bytes[offset..offset + len].to_vec())?);
+            offset += len;
+        }
+
+        Ok(NetworkMessage { changes })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct ChatMessage {
     id: Uuid,
     user_id: String,
@@ -32,18 +70,66 @@ impl ChatMessage {
     }
 }
 
+/// Materialized unread-count state, updated incrementally: `refresh` folds
+/// in whatever changed since the last call (the message log grew and/or a
+/// peer's read marker advanced) instead of rescanning the whole document.
+#[derive(Default)]
+struct UnreadCache {
+    counts: HashMap<String, usize>,
+    last_list_len: usize,
+    last_markers: HashMap<String, u64>,
+    /// Every distinct message author seen so far. Folded in incrementally
+    /// from just the messages appended since `last_list_len`, so a
+    /// participant who's never set a read marker still gets seeded into
+    /// `counts` without rescanning the whole log on every call.
+    known_authors: HashSet<String>,
+}
+
+/// Actor id reserved for the one-time genesis transaction below. Never
+/// reused by a live `ChatUser` (those get a fresh random actor from
+/// `AutoCommit::load`), so it can't collide with a real replica's ops.
+const GENESIS_ACTOR: [u8; 16] = [0; 16];
+
+/// Builds the document every `ChatUser` starts from: both shared root
+/// containers (`"messages"` and `"read_markers"`) already created by one
+/// fixed, deterministic op. Without this, two users who chat before ever
+/// syncing would each lazily `put_object` their own copy of these
+/// containers at the same root key; when they finally sync, Automerge picks
+/// one winner and the loser's container - and every message or marker
+/// inserted into it - silently disappears. Starting every replica from
+/// identical genesis bytes means there's only ever one such object to begin
+/// with, so there's nothing left to race.
+pub(crate) fn genesis_doc_bytes() -> Vec<u8> {
+    let mut doc = AutoCommit::new().with_actor(automerge::ActorId::from(GENESIS_ACTOR.to_vec()));
+    doc.put_object(ROOT, "messages", ObjType::List)
+        .expect("create messages list");
+    doc.put_object(ROOT, "read_markers", ObjType::Map)
+        .expect("create read_markers map");
+    doc.save()
+}
+
 struct ChatUser {
     id: String,
     doc: AutoCommit,
+    /// Per-peer sync progress, keyed by `net::PeerId`. Lives here (rather
+    /// than on the transport) because it's a property of this replica's
+    /// relationship with each peer, not of the pipe moving the bytes.
+    peer_states: HashMap<net::PeerId, sync::State>,
+    /// Behind a `RefCell` so read-only methods like `get_messages` and
+    /// `unread_counts` can keep the materialized view current as a side
+    /// effect without becoming `&mut self`.
+    unread_cache: RefCell<UnreadCache>,
 }
 
 impl ChatUser {
     fn new(user_id: &str) -> Result<Self, automerge::AutomergeError> {
-        let doc = AutoCommit::new();
+        let doc = AutoCommit::load(&genesis_doc_bytes())?;
 
         Ok(ChatUser {
             id: user_id.to_string(),
             doc,
+            peer_states: HashMap::new(),
+            unread_cache: RefCell::new(UnreadCache::default()),
         })
     }
 
@@ -51,26 +137,20 @@ impl ChatUser {
         &mut self,
         chat_message: ChatMessage,
     ) -> Result<NetworkMessage, automerge::AutomergeError> {
-        
-
-        // Create message as a Map entry
-        let message_obj = self
-            .doc
-            .put_object(automerge::ROOT, &chat_message.id.to_string(), ObjType::Map)?;
-        self.doc
-            .put(&message_obj, "id", chat_message.id.to_string())?;
-        self.doc
-            .put(&message_obj, "user_id", chat_message.user_id)?;
-        self.doc
-            .put(&message_obj, "content", chat_message.content.to_string())?;
-        self.doc
-            .put(&message_obj, "timestamp", chat_message.timestamp)?;
-
-        let change = self.doc.get_last_local_change().unwrap();
+        add_message_to_doc(&mut self.doc, chat_message)
+    }
 
-        Ok(NetworkMessage {
-            changes: vec![change.clone()],
-        })
+    /// Edits an existing message's content in place. Unlike overwriting
+    /// `content` with `put`, this diffs the old and new text and applies
+    /// `splice_text`, so two users editing the same message concurrently
+    /// merge character-by-character via Automerge's RGA instead of one
+    /// edit clobbering the other.
+    fn edit_message(
+        &mut self,
+        id: Uuid,
+        new_content: &str,
+    ) -> Result<NetworkMessage, automerge::AutomergeError> {
+        edit_message_in_doc(&mut self.doc, id, new_content)
     }
 
     fn receive_message(
@@ -84,38 +164,71 @@ impl ChatUser {
     }
 
     fn get_messages(&self) -> Vec<ChatMessage> {
-        let mut messages = Vec::new();
+        self.refresh_unread_cache();
+        read_messages_from_doc(&self.doc)
+    }
 
-        for entry in self.doc.map_range(ROOT, ..) {
-            let user_id = match self.doc.get(&entry.id, "user_id") {
-                Ok(Some((Value::Scalar(user_id), _))) => user_id.to_str().unwrap().to_string(),
-                _ => continue,
-            };
-            let content = match self.doc.get(&entry.id, "content") {
-                Ok(Some((Value::Scalar(content), _))) => content.to_str().unwrap().to_string(),
-                _ => continue,
-            };
-            
-            let id = match self.doc.get(&entry.id, "id") {
-                Ok(Some((Value::Scalar(content), _))) => content.to_str().unwrap().to_string(),
-                _ => continue,
-            };
+    /// Advances this user's read marker to the end of the current message
+    /// log and returns the resulting change as a `NetworkMessage` to
+    /// broadcast. The marker itself lives in the CRDT (under
+    /// `"read_markers"`), so it syncs and converges across peers like
+    /// everything else.
+    fn mark_read(&mut self) -> Result<NetworkMessage, automerge::AutomergeError> {
+        let len = current_message_count(&self.doc) as u64;
+        set_read_marker(&mut self.doc, &self.id, len)?;
+        self.refresh_unread_cache();
 
-            let timestamp = match self.doc.get(&entry.id, "timestamp") {
-                Ok(Some((Value::Scalar(timestamp), _))) => timestamp.to_u64().unwrap(),
-                _ => continue,
-            };
+        let change = self.doc.get_last_local_change().unwrap();
+        Ok(NetworkMessage {
+            changes: vec![change.clone()],
+        })
+    }
 
-            messages.push(ChatMessage {
-                id: Uuid::parse_str(&id).unwrap(),
-                user_id,
-                content,
-                timestamp,
-            });
+    /// The materialized per-user unread count: messages in the log past
+    /// each user's read marker.
+    fn unread_counts(&self) -> HashMap<String, usize> {
+        self.refresh_unread_cache();
+        self.unread_cache.borrow().counts.clone()
+    }
+
+    /// Folds in whatever changed since the last refresh: if the message log
+    /// grew, every user whose marker didn't move gets the same increment
+    /// added to their cached count; if a marker moved, that user's count is
+    /// recomputed directly from its new position instead.
+    fn refresh_unread_cache(&self) {
+        let len = current_message_count(&self.doc) as u64;
+        let markers = read_markers(&self.doc);
+
+        let mut cache = self.unread_cache.borrow_mut();
+        let grown = len.saturating_sub(cache.last_list_len as u64);
+
+        if grown > 0 {
+            let new_authors = message_authors_since(&self.doc, cache.last_list_len);
+            cache.known_authors.extend(new_authors);
+        }
+
+        let users: HashSet<String> = markers
+            .keys()
+            .cloned()
+            .chain(cache.counts.keys().cloned())
+            .chain(cache.known_authors.iter().cloned())
+            .collect();
+
+        let mut next_counts = HashMap::with_capacity(users.len());
+        for user in users {
+            let marker = markers.get(&user).copied().unwrap_or(0);
+            let marker_moved = cache.last_markers.get(&user).copied() != Some(marker);
+            let unread = if marker_moved {
+                len.saturating_sub(marker) as usize
+            } else {
+                cache.counts.get(&user).copied().unwrap_or(0) + grown as usize
+            };
+            next_counts.insert(user, unread);
         }
 
-        messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        messages
+        cache.counts = next_counts;
+        cache.last_list_len = len as usize;
+        cache.last_markers = markers;
     }
 
     fn print_messages(&self) {
@@ -141,12 +254,269 @@ fn broadcast_message(
     Ok(())
 }
 
+/// Returns the `ObjId` of the root `"messages"` list. Every `ChatUser` forks
+/// from `genesis_doc_bytes`, so this always exists; the `put_object` here is
+/// only a defensive fallback for a document that didn't (e.g. in a test),
+/// not the normal path - relying on it in the normal path is exactly what
+/// let two never-synced replicas race to create their own copy. Every
+/// message lives as an entry in this single Automerge List rather than
+/// scattered across top-level map keys, so the RGA gives concurrent inserts
+/// from different replicas a deterministic, convergent order with no clock
+/// comparison needed.
+fn messages_list(doc: &mut AutoCommit) -> Result<ObjId, automerge::AutomergeError> {
+    if let Some((_, list)) = doc.get(ROOT, "messages")? {
+        return Ok(list);
+    }
+    doc.put_object(ROOT, "messages", ObjType::List)
+}
+
+/// Returns how many messages are currently in the `"messages"` list.
+fn current_message_count(doc: &AutoCommit) -> usize {
+    let Ok(Some((_, list))) = doc.get(ROOT, "messages") else {
+        return 0;
+    };
+    doc.length(&list)
+}
+
+/// Returns the `ObjId` of the root `"read_markers"` map. Every `ChatUser`
+/// forks from `genesis_doc_bytes`, so this always exists; the `put_object`
+/// here is only a defensive fallback for a document that didn't (e.g. in a
+/// test), not the normal path.
+fn read_markers_map(doc: &mut AutoCommit) -> Result<ObjId, automerge::AutomergeError> {
+    if let Some((_, map)) = doc.get(ROOT, "read_markers")? {
+        return Ok(map);
+    }
+    doc.put_object(ROOT, "read_markers", ObjType::Map)
+}
+
+/// Records `user_id`'s read marker (an index into the `"messages"` list) in
+/// the CRDT, so it syncs and converges across peers the same way the
+/// messages themselves do.
+fn set_read_marker(
+    doc: &mut AutoCommit,
+    user_id: &str,
+    marker: u64,
+) -> Result<(), automerge::AutomergeError> {
+    let map = read_markers_map(doc)?;
+    doc.put(&map, user_id, marker)?;
+    Ok(())
+}
+
+/// Reads every user's current read marker out of the CRDT.
+fn read_markers(doc: &AutoCommit) -> HashMap<String, u64> {
+    let mut markers = HashMap::new();
+
+    let Ok(Some((_, map))) = doc.get(ROOT, "read_markers") else {
+        return markers;
+    };
+
+    for entry in doc.map_range(&map, ..) {
+        if let Value::Scalar(value) = entry.value {
+            if let Some(marker) = value.to_u64() {
+                markers.insert(entry.key.to_string(), marker);
+            }
+        }
+    }
+
+    markers
+}
+
+/// Returns every distinct `user_id` among messages in the `"messages"` list
+/// at index `start` or later, so `refresh_unread_cache` can fold in just
+/// what's new since its last call instead of rescanning the whole log to
+/// seed unread counts for a participant who hasn't set a read marker yet.
+fn message_authors_since(doc: &AutoCommit, start: usize) -> HashSet<String> {
+    let Ok(Some((_, list))) = doc.get(ROOT, "messages") else {
+        return HashSet::new();
+    };
+
+    doc.list_range(&list, start..)
+        .filter_map(|entry| match doc.get(&entry.id, "user_id") {
+            Ok(Some((Value::Scalar(value), _))) => value.to_str().map(|s| s.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Finds the list entry whose `id` field matches `id`, if any.
+fn find_message_obj(doc: &AutoCommit, list: &ObjId, id: Uuid) -> Option<ObjId> {
+    let target = id.to_string();
+    doc.list_range(list, ..).find_map(|entry| {
+        match doc.get(&entry.id, "id") {
+            Ok(Some((Value::Scalar(value), _))) if value.to_str() == Some(target.as_str()) => {
+                Some(entry.id)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Pushes a message onto the `"messages"` list and returns the resulting
+/// change as a `NetworkMessage` ready to broadcast. Pulled out of
+/// `ChatUser::add_message` so the sync simulation harness in `sim` can drive
+/// plain `AutoCommit` documents the same way `ChatUser` does.
+fn add_message_to_doc(
+    doc: &mut AutoCommit,
+    chat_message: ChatMessage,
+) -> Result<NetworkMessage, automerge::AutomergeError> {
+    let list = messages_list(doc)?;
+    let index = doc.length(&list);
+    let message_obj = doc.insert_object(&list, index, ObjType::Map)?;
+    doc.put(&message_obj, "id", chat_message.id.to_string())?;
+    doc.put(&message_obj, "user_id", chat_message.user_id)?;
+
+    // `content` is an Automerge Text object rather than a scalar string, so
+    // concurrent edits to the same message merge character-by-character
+    // instead of one overwrite clobbering the other.
+    let content_obj = doc.put_object(&message_obj, "content", ObjType::Text)?;
+    doc.splice_text(&content_obj, 0, 0, &chat_message.content)?;
+
+    // Kept for display only; ordering now comes from the list itself.
+    doc.put(&message_obj, "timestamp", chat_message.timestamp)?;
+
+    let change = doc.get_last_local_change().unwrap();
+
+    Ok(NetworkMessage {
+        changes: vec![change.clone()],
+    })
+}
+
+/// Applies `new_content` to an existing message's `content` Text object by
+/// splicing only the span that actually changed (see `diff_text`), so a
+/// concurrent edit from another peer to a different part of the same
+/// message merges rather than one replacing the other outright.
+fn edit_message_in_doc(
+    doc: &mut AutoCommit,
+    id: Uuid,
+    new_content: &str,
+) -> Result<NetworkMessage, automerge::AutomergeError> {
+    let list = messages_list(doc)?;
+    // A peer can know a message's id (e.g. via gossip from a third party)
+    // before the change that created it has synced here, so this can't
+    // assume the lookup succeeds - surface it as an error instead of
+    // panicking the whole process.
+    let message_obj = find_message_obj(doc, &list, id)
+        .ok_or_else(|| automerge::AutomergeError::InvalidObjId(id.to_string()))?;
+    let (_, content_obj) = doc
+        .get(&message_obj, "content")?
+        .ok_or(automerge::AutomergeError::Fail)?;
+
+    let old_content = doc.text(&content_obj)?;
+    let (start, delete_count, insert) = diff_text(&old_content, new_content);
+    doc.splice_text(&content_obj, start, delete_count, &insert)?;
+
+    let change = doc.get_last_local_change().unwrap();
+
+    Ok(NetworkMessage {
+        changes: vec![change.clone()],
+    })
+}
+
+/// Finds the shortest (start, delete_count, insert) edit that turns `old`
+/// into `new` by trimming the common prefix and suffix, so `splice_text`
+/// only touches the span that actually changed.
+fn diff_text(old: &str, new: &str) -> (usize, usize, String) {
+    let old_chars: Vec<char> = old.chars().collect();
+    let new_chars: Vec<char> = new.chars().collect();
+
+    let mut prefix = 0;
+    while prefix < old_chars.len()
+        && prefix < new_chars.len()
+        && old_chars[prefix] == new_chars[prefix]
+    {
+        prefix += 1;
+    }
+
+    let mut suffix = 0;
+    while suffix < old_chars.len() - prefix
+        && suffix < new_chars.len() - prefix
+        && old_chars[old_chars.len() - 1 - suffix] == new_chars[new_chars.len() - 1 - suffix]
+    {
+        suffix += 1;
+    }
+
+    let delete_count = old_chars.len() - prefix - suffix;
+    let insert: String = new_chars[prefix..new_chars.len() - suffix].iter().collect();
+
+    (prefix, delete_count, insert)
+}
+
+/// Reads every message back out of the `"messages"` list, in list order.
+/// That order is already the convergent, clock-free order every replica
+/// agrees on, so unlike the old map-keyed storage this needs no sorting.
+fn read_messages_from_doc(doc: &AutoCommit) -> Vec<ChatMessage> {
+    let mut messages = Vec::new();
+
+    let Ok(Some((_, list))) = doc.get(ROOT, "messages") else {
+        return messages;
+    };
+
+    for entry in doc.list_range(&list, ..) {
+        let user_id = match doc.get(&entry.id, "user_id") {
+            Ok(Some((Value::Scalar(user_id), _))) => user_id.to_str().unwrap().to_string(),
+            _ => continue,
+        };
+        let content = match doc.get(&entry.id, "content") {
+            Ok(Some((Value::Object(ObjType::Text), content_obj))) => {
+                doc.text(&content_obj).unwrap()
+            }
+            _ => continue,
+        };
+
+        let id = match doc.get(&entry.id, "id") {
+            Ok(Some((Value::Scalar(content), _))) => content.to_str().unwrap().to_string(),
+            _ => continue,
+        };
+
+        let timestamp = match doc.get(&entry.id, "timestamp") {
+            Ok(Some((Value::Scalar(timestamp), _))) => timestamp.to_u64().unwrap(),
+            _ => continue,
+        };
+
+        messages.push(ChatMessage {
+            id: Uuid::parse_str(&id).unwrap(),
+            user_id,
+            content,
+            timestamp,
+        });
+    }
+
+    messages
+}
+
+/// Runs the generate/receive sync-message handshake between two documents
+/// until neither side has anything left to send. `main` uses it to catch
+/// `user3` up on `user1`'s history. `ChatUser::resync_peer` in `net` runs
+/// the same generate/receive exchange with its own loop over a `Transport`
+/// to bring a joining or reconnecting peer back up to date.
+fn sync_until_converged(
+    a: &mut AutoCommit,
+    a_state: &mut sync::State,
+    b: &mut AutoCommit,
+    b_state: &mut sync::State,
+) -> Result<(), automerge::AutomergeError> {
+    loop {
+        let a_to_b = a.sync().generate_sync_message(a_state);
+        if let Some(message) = a_to_b.as_ref() {
+            b.sync().receive_sync_message(b_state, message.clone())?;
+        }
+        let b_to_a = b.sync().generate_sync_message(b_state);
+        if let Some(message) = b_to_a.as_ref() {
+            a.sync().receive_sync_message(a_state, message.clone())?;
+        }
+        if a_to_b.is_none() && b_to_a.is_none() {
+            break;
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), automerge::AutomergeError> {
     let mut user1 = ChatUser::new("user1")?;
     let mut user2 = ChatUser::new("user2")?;
 
     // User 1 sends a message
-    let mut user1_message1 = ChatMessage::new("user1", "Hello, anyone there?");
+    let user1_message1 = ChatMessage::new("user1", "Hello, anyone there?");
     let msg1 = user1.add_message(user1_message1.clone())?;
     broadcast_message(&msg1, &mut [&mut user2])?;
 
@@ -181,38 +551,192 @@ fn main() -> Result<(), automerge::AutomergeError> {
         .sync()
         .receive_sync_message(&mut user3_state, message1to2)?;
 
-    loop {
-        let two_to_one = user3.doc.sync().generate_sync_message(&mut user3_state);
-        if let Some(message) = two_to_one.as_ref() {
-            user1
-                .doc
-                .sync()
-                .receive_sync_message(&mut user1_state, message.clone())?;
-        }
-        let one_to_two = user1.doc.sync().generate_sync_message(&mut user1_state);
-        if let Some(message) = one_to_two.as_ref() {
-            user3
-                .doc
-                .sync()
-                .receive_sync_message(&mut user3_state, message.clone())?;
-        }
-        if two_to_one.is_none() && one_to_two.is_none() {
-            break;
-        }
-    }
+    sync_until_converged(&mut user3.doc, &mut user3_state, &mut user1.doc, &mut user1_state)?;
 
     // User 3 sends a message
     let msg4 = user3.add_message(ChatMessage::new("user3", "Hey, can I join?"))?;
     broadcast_message(&msg4, &mut [&mut user1, &mut user2])?;
     
     // user 1 modified the first message
-    user1_message1.content = "Hello, anyone there??? [Edit]".to_string();
-    let msg1 = user1.add_message(user1_message1)?;
+    let msg1 = user1.edit_message(user1_message1.id, "Hello, anyone there??? [Edit]")?;
     broadcast_message(&msg1, &mut [&mut user2, &mut user3])?;
 
     user1.print_messages();
     user2.print_messages();
     user3.print_messages();
 
+    // User 2 reads up to the current end of the log; broadcast the marker
+    // so everyone else's unread counts reflect it too.
+    let read_msg = user2.mark_read()?;
+    broadcast_message(&read_msg, &mut [&mut user1, &mut user3])?;
+    println!("user1's view of unread counts: {:?}", user1.unread_counts());
+    println!("user2's view of unread counts: {:?}", user2.unread_counts());
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two `ChatUser`s that each post a message before ever syncing must
+    /// both still have both messages afterward. This is the scenario that
+    /// breaks if `"messages"`/`"read_markers"` are lazily created per actor:
+    /// each user would independently `put_object` its own list, and syncing
+    /// would silently keep only one of them.
+    #[test]
+    fn never_synced_peers_both_keep_their_pre_sync_messages() {
+        let mut user1 = ChatUser::new("user1").unwrap();
+        let mut user2 = ChatUser::new("user2").unwrap();
+
+        user1
+            .add_message(ChatMessage::new("user1", "hello from user1"))
+            .unwrap();
+        user2
+            .add_message(ChatMessage::new("user2", "hello from user2"))
+            .unwrap();
+
+        let mut user1_state = sync::State::new();
+        let mut user2_state = sync::State::new();
+        sync_until_converged(
+            &mut user1.doc,
+            &mut user1_state,
+            &mut user2.doc,
+            &mut user2_state,
+        )
+        .unwrap();
+
+        assert_eq!(user1.get_messages().len(), 2);
+        assert_eq!(user1.get_messages(), user2.get_messages());
+    }
+
+    /// Same scenario as `never_synced_peers_both_keep_their_pre_sync_messages`,
+    /// but for `"read_markers"`: each user marks themselves caught up before
+    /// ever syncing, and both markers must survive the merge.
+    #[test]
+    fn never_synced_peers_both_keep_their_pre_sync_read_markers() {
+        let mut user1 = ChatUser::new("user1").unwrap();
+        let mut user2 = ChatUser::new("user2").unwrap();
+
+        user1
+            .add_message(ChatMessage::new("user1", "hello from user1"))
+            .unwrap();
+        user2
+            .add_message(ChatMessage::new("user2", "hello from user2"))
+            .unwrap();
+
+        user1.mark_read().unwrap();
+        user2.mark_read().unwrap();
+
+        let mut user1_state = sync::State::new();
+        let mut user2_state = sync::State::new();
+        sync_until_converged(
+            &mut user1.doc,
+            &mut user1_state,
+            &mut user2.doc,
+            &mut user2_state,
+        )
+        .unwrap();
+
+        let markers = read_markers(&user1.doc);
+        assert!(markers.contains_key("user1"));
+        assert!(markers.contains_key("user2"));
+        assert_eq!(markers, read_markers(&user2.doc));
+    }
+
+    /// Applies a `diff_text` result to `old` the same way `edit_message_in_doc`
+    /// applies it via `splice_text`, so tests can assert against the final
+    /// string instead of hand-computing indices.
+    fn apply_diff(old: &str, (start, delete_count, insert): (usize, usize, String)) -> String {
+        let mut chars: Vec<char> = old.chars().collect();
+        chars.splice(start..start + delete_count, insert.chars());
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn diff_text_trims_shared_prefix_and_suffix() {
+        let old = "hello world";
+        let new = "hello there world";
+        let diff = diff_text(old, new);
+        assert_eq!(diff, (6, 0, "there ".to_string()));
+        assert_eq!(apply_diff(old, diff), new);
+    }
+
+    #[test]
+    fn diff_text_is_empty_for_identical_strings() {
+        assert_eq!(diff_text("same", "same"), (4, 0, String::new()));
+    }
+
+    #[test]
+    fn diff_text_handles_a_full_replacement() {
+        let old = "abc";
+        let new = "xyz";
+        let diff = diff_text(old, new);
+        assert_eq!(apply_diff(old, diff), new);
+    }
+
+    #[test]
+    fn edit_message_errors_on_an_unknown_id() {
+        let mut user = ChatUser::new("user1").unwrap();
+        let result = user.edit_message(Uuid::new_v4(), "no such message");
+        assert!(matches!(
+            result,
+            Err(automerge::AutomergeError::InvalidObjId(_))
+        ));
+    }
+
+    #[test]
+    fn edit_message_splices_content_in_place() {
+        let mut user = ChatUser::new("user1").unwrap();
+        let message = ChatMessage::new("user1", "hello world");
+        let id = message.id;
+        user.add_message(message).unwrap();
+
+        user.edit_message(id, "hello there world").unwrap();
+
+        let messages = user.get_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "hello there world");
+    }
+
+    #[test]
+    fn unread_counts_seeds_every_message_author_without_a_marker() {
+        let mut user = ChatUser::new("user1").unwrap();
+        user.add_message(ChatMessage::new("user1", "hi")).unwrap();
+        user.add_message(ChatMessage::new("user2", "hi back"))
+            .unwrap();
+
+        let counts = user.unread_counts();
+        assert_eq!(counts.get("user1"), Some(&2));
+        assert_eq!(counts.get("user2"), Some(&2));
+    }
+
+    #[test]
+    fn refresh_unread_cache_folds_growth_and_recomputes_on_marker_advance() {
+        let mut user = ChatUser::new("user1").unwrap();
+
+        user.add_message(ChatMessage::new("user1", "msg1")).unwrap();
+        user.add_message(ChatMessage::new("user2", "msg2")).unwrap();
+
+        // Neither user has a marker yet, so both start fully unread.
+        let counts = user.unread_counts();
+        assert_eq!(counts.get("user1"), Some(&2));
+        assert_eq!(counts.get("user2"), Some(&2));
+
+        // user2 catches up to the current end of the log (index 2): its
+        // count should be recomputed from the new marker, not incremented.
+        set_read_marker(&mut user.doc, "user2", 2).unwrap();
+
+        let counts = user.unread_counts();
+        assert_eq!(counts.get("user2"), Some(&0));
+        assert_eq!(counts.get("user1"), Some(&2));
+
+        // A third message arrives: user1's marker never moved, so its count
+        // just grows by the increment; user2's fixed marker means its count
+        // grows from the new baseline instead.
+        user.add_message(ChatMessage::new("user1", "msg3")).unwrap();
+        let counts = user.unread_counts();
+        assert_eq!(counts.get("user1"), Some(&3));
+        assert_eq!(counts.get("user2"), Some(&1));
+    }
+}
@@ -0,0 +1,393 @@
+//! A pluggable network transport for `ChatUser`, plus a TCP implementation.
+//!
+//! `broadcast_message` in `main` just calls `receive_message` on in-process
+//! `&mut ChatUser` slices — there's no real network underneath it. This
+//! module adds that layer: a `Transport` trait any pipe can implement, and a
+//! `TcpTransport` that actually listens on and dials sockets. `ChatUser`
+//! drives a transport through the same sync handshake `main` already uses
+//! for a brand new peer (see `sync_until_converged`), and reuses it whenever
+//! a peer reconnects after a dropped connection.
+
+use crate::{ChatUser, NetworkMessage};
+use automerge::sync::{self, SyncDoc};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub(crate) type PeerId = String;
+
+/// Something a `ChatUser` can exchange bytes with, without caring whether
+/// the other end is in the same process or across a socket.
+pub(crate) trait Transport {
+    fn send(&mut self, peer: &PeerId, bytes: &[u8]) -> io::Result<()>;
+    fn recv(&mut self) -> Option<(PeerId, Vec<u8>)>;
+    /// Puts a message back so a later `recv` returns it again. Used when a
+    /// caller pulls a message meant for someone else off the front of the
+    /// queue (e.g. `resync_peer` filtering for one peer) and needs to avoid
+    /// losing it.
+    fn requeue(&mut self, peer: PeerId, bytes: Vec<u8>);
+}
+
+/// Connection lifecycle notifications the transport surfaces to the chat
+/// layer so it can keep per-peer sync state consistent with who's actually
+/// reachable.
+pub(crate) enum PeerEvent {
+    Joined(PeerId),
+    Left(PeerId),
+}
+
+fn write_frame(stream: &mut TcpStream, bytes: &[u8]) -> io::Result<()> {
+    stream.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    stream.write_all(bytes)
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 4];
+    stream.read_exact(&mut len_bytes)?;
+    let mut buf = vec![0u8; u32::from_be_bytes(len_bytes) as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A `Transport` backed by real TCP sockets. Every connection (inbound or
+/// outbound) starts with a handshake frame carrying the remote's `PeerId`,
+/// then becomes a stream of length-prefixed frames.
+pub(crate) struct TcpTransport {
+    local_addr: std::net::SocketAddr,
+    connections: Arc<Mutex<HashMap<PeerId, TcpStream>>>,
+    inbox: Receiver<(PeerId, Vec<u8>)>,
+    inbox_tx: Sender<(PeerId, Vec<u8>)>,
+    events: Receiver<PeerEvent>,
+    events_tx: Sender<PeerEvent>,
+}
+
+impl TcpTransport {
+    /// Starts a listener thread on `addr` that accepts inbound connections
+    /// and spawns a reader thread per connection. Bind to port `0` to let
+    /// the OS pick a free port, then read it back with `local_addr`.
+    pub(crate) fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let (inbox_tx, inbox) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+
+        let accept_connections = Arc::clone(&connections);
+        let accept_inbox_tx = inbox_tx.clone();
+        let accept_events_tx = events_tx.clone();
+        thread::spawn(move || {
+            for incoming in listener.incoming() {
+                let Ok(mut stream) = incoming else { continue };
+                let Ok(handshake) = read_frame(&mut stream) else { continue };
+                let peer_id = String::from_utf8_lossy(&handshake).into_owned();
+
+                spawn_reader(
+                    peer_id.clone(),
+                    stream.try_clone().expect("clone accepted stream"),
+                    Arc::clone(&accept_connections),
+                    accept_inbox_tx.clone(),
+                    accept_events_tx.clone(),
+                );
+
+                accept_connections
+                    .lock()
+                    .unwrap()
+                    .insert(peer_id.clone(), stream);
+                let _ = accept_events_tx.send(PeerEvent::Joined(peer_id));
+            }
+        });
+
+        Ok(TcpTransport {
+            local_addr,
+            connections,
+            inbox,
+            inbox_tx,
+            events,
+            events_tx,
+        })
+    }
+
+    /// The address this transport ended up listening on, useful for tests
+    /// and callers that bound to port `0`.
+    pub(crate) fn local_addr(&self) -> std::net::SocketAddr {
+        self.local_addr
+    }
+
+    /// Dials out to `peer` at `addr`, announcing `my_id` as the handshake.
+    pub(crate) fn connect(
+        &mut self,
+        my_id: &PeerId,
+        peer: PeerId,
+        addr: impl ToSocketAddrs,
+    ) -> io::Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        write_frame(&mut stream, my_id.as_bytes())?;
+
+        spawn_reader(
+            peer.clone(),
+            stream.try_clone()?,
+            Arc::clone(&self.connections),
+            self.inbox_tx.clone(),
+            self.events_tx.clone(),
+        );
+
+        self.connections.lock().unwrap().insert(peer.clone(), stream);
+        let _ = self.events_tx.send(PeerEvent::Joined(peer));
+        Ok(())
+    }
+
+    /// Drains any connect/disconnect notifications since the last poll.
+    pub(crate) fn poll_events(&mut self) -> Vec<PeerEvent> {
+        std::iter::from_fn(|| self.events.try_recv().ok()).collect()
+    }
+}
+
+/// Reads length-prefixed frames off `stream` and forwards them to `inbox_tx`
+/// until the connection breaks, at which point the peer is dropped and a
+/// `PeerEvent::Left` is raised.
+fn spawn_reader(
+    peer_id: PeerId,
+    mut stream: TcpStream,
+    connections: Arc<Mutex<HashMap<PeerId, TcpStream>>>,
+    inbox_tx: Sender<(PeerId, Vec<u8>)>,
+    events_tx: Sender<PeerEvent>,
+) {
+    thread::spawn(move || loop {
+        match read_frame(&mut stream) {
+            Ok(bytes) => {
+                if inbox_tx.send((peer_id.clone(), bytes)).is_err() {
+                    break;
+                }
+            }
+            Err(_) => {
+                connections.lock().unwrap().remove(&peer_id);
+                let _ = events_tx.send(PeerEvent::Left(peer_id));
+                break;
+            }
+        }
+    });
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, peer: &PeerId, bytes: &[u8]) -> io::Result<()> {
+        let mut connections = self.connections.lock().unwrap();
+        let stream = connections
+            .get_mut(peer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "peer not connected"))?;
+
+        let result = write_frame(stream, bytes);
+        if result.is_err() {
+            connections.remove(peer);
+            let _ = self.events_tx.send(PeerEvent::Left(peer.clone()));
+        }
+        result
+    }
+
+    fn recv(&mut self) -> Option<(PeerId, Vec<u8>)> {
+        self.inbox.try_recv().ok()
+    }
+
+    fn requeue(&mut self, peer: PeerId, bytes: Vec<u8>) {
+        let _ = self.inbox_tx.send((peer, bytes));
+    }
+}
+
+impl ChatUser {
+    /// Brings `peer` up to date over `transport`, whether this is the first
+    /// time we've ever synced with it or it's reconnecting after a drop.
+    /// Runs the same generate/receive exchange as `sync_until_converged`
+    /// (which `main` uses to catch a fresh peer up), in its own loop driven
+    /// over a `Transport` instead of directly between two in-process
+    /// `AutoCommit`s.
+    fn resync_peer(&mut self, transport: &mut dyn Transport, peer: &PeerId) {
+        self.peer_states
+            .entry(peer.clone())
+            .or_insert_with(sync::State::new);
+
+        // Messages from other peers that show up while we're resyncing
+        // `peer`. Buffered locally rather than requeued immediately, since
+        // requeuing straight back into the transport would just hand them
+        // to the very next `recv` below and spin forever instead of ever
+        // observing `None`.
+        let mut deferred = Vec::new();
+
+        loop {
+            let outbound = {
+                let state = self.peer_states.get_mut(peer).unwrap();
+                self.doc.sync().generate_sync_message(state)
+            };
+            if let Some(message) = &outbound {
+                if transport.send(peer, &message.encode()).is_err() {
+                    self.drop_peer(peer);
+                    break;
+                }
+            }
+
+            match transport.recv() {
+                Some((from, bytes)) if &from == peer => {
+                    let Ok(message) = sync::Message::decode(&bytes) else {
+                        continue;
+                    };
+                    let state = self.peer_states.get_mut(peer).unwrap();
+                    if self.doc.sync().receive_sync_message(state, message).is_err() {
+                        break;
+                    }
+                }
+                Some(other) => deferred.push(other),
+                None if outbound.is_none() => break,
+                None => continue,
+            }
+        }
+
+        // Put everything we set aside back so the caller's next `recv`
+        // still sees it.
+        for (from, bytes) in deferred {
+            transport.requeue(from, bytes);
+        }
+    }
+
+    /// Forgets everything we knew about `peer`'s sync progress. Called when
+    /// a send to it fails, so a future reconnect starts a clean resync
+    /// rather than resuming from stale state.
+    fn drop_peer(&mut self, peer: &PeerId) {
+        self.peer_states.remove(peer);
+        println!("[{}] lost connection to {}", self.id, peer);
+    }
+
+    /// Applies any transport-level connect/disconnect events: resyncs newly
+    /// joined peers and clears state for ones that left. Call this
+    /// periodically alongside `poll_messages` when driving a real
+    /// `Transport`.
+    pub(crate) fn handle_peer_events(&mut self, transport: &mut dyn Transport, events: Vec<PeerEvent>) {
+        for event in events {
+            match event {
+                PeerEvent::Joined(peer) => self.resync_peer(transport, &peer),
+                PeerEvent::Left(peer) => self.drop_peer(&peer),
+            }
+        }
+    }
+
+    /// Broadcasts a local change to `peer` as a raw `NetworkMessage` blob
+    /// (as opposed to a sync message), dropping and clearing state on the
+    /// peer if the send fails.
+    pub(crate) fn send_message(
+        &mut self,
+        transport: &mut dyn Transport,
+        peer: &PeerId,
+        msg: &NetworkMessage,
+    ) {
+        if transport.send(peer, &msg.to_bytes()).is_err() {
+            self.drop_peer(peer);
+        }
+    }
+
+    /// Drains every `NetworkMessage` a peer has sent via `send_message` and
+    /// applies it. Call this periodically alongside `handle_peer_events`;
+    /// it's the receiving half of `send_message`, the same way
+    /// `resync_peer` is the receiving half of the sync handshake. Anything
+    /// that doesn't decode as a `NetworkMessage` is skipped rather than
+    /// treated as fatal, since a malformed or unexpected frame from one peer
+    /// shouldn't take down processing of the rest of the inbox.
+    pub(crate) fn poll_messages(&mut self, transport: &mut dyn Transport) {
+        while let Some((_from, bytes)) = transport.recv() {
+            let Ok(msg) = NetworkMessage::from_bytes(&bytes) else {
+                continue;
+            };
+            let _ = self.receive_message(&msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatMessage;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Binds two real `TcpTransport`s on localhost, has each resync the
+    /// other, and checks both `ChatUser`s converge. Guards against
+    /// `resync_peer` silently dropping messages that arrive for a different
+    /// peer mid-resync (and against the socket/thread plumbing in general,
+    /// which nothing else in the test suite exercises).
+    #[test]
+    fn tcp_transport_resync_converges() {
+        let mut alice = ChatUser::new("alice").unwrap();
+        let mut bob = ChatUser::new("bob").unwrap();
+
+        alice
+            .add_message(ChatMessage::new("alice", "hi from alice"))
+            .unwrap();
+        bob.add_message(ChatMessage::new("bob", "hi from bob"))
+            .unwrap();
+
+        let mut alice_transport = TcpTransport::bind("127.0.0.1:0").unwrap();
+        let mut bob_transport = TcpTransport::bind("127.0.0.1:0").unwrap();
+        let alice_addr = alice_transport.local_addr();
+        let bob_addr = bob_transport.local_addr();
+
+        alice_transport
+            .connect(&"alice".to_string(), "bob".to_string(), bob_addr)
+            .unwrap();
+        bob_transport
+            .connect(&"bob".to_string(), "alice".to_string(), alice_addr)
+            .unwrap();
+
+        // Let the accept threads finish the handshake before resyncing.
+        thread::sleep(Duration::from_millis(100));
+
+        for _ in 0..5 {
+            alice.resync_peer(&mut alice_transport, &"bob".to_string());
+            bob.resync_peer(&mut bob_transport, &"alice".to_string());
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        assert_eq!(alice.get_messages().len(), 2);
+        assert_eq!(bob.get_messages().len(), 2);
+        assert_eq!(alice.get_messages(), bob.get_messages());
+    }
+
+    /// After two peers resync, a further local edit broadcast with
+    /// `send_message` must show up on the other side once it calls
+    /// `poll_messages` - the receiving half of `send_message` that nothing
+    /// else in the codebase drives.
+    #[test]
+    fn tcp_transport_delivers_network_messages_after_resync() {
+        let mut alice = ChatUser::new("alice").unwrap();
+        let mut bob = ChatUser::new("bob").unwrap();
+
+        let mut alice_transport = TcpTransport::bind("127.0.0.1:0").unwrap();
+        let mut bob_transport = TcpTransport::bind("127.0.0.1:0").unwrap();
+        let alice_addr = alice_transport.local_addr();
+        let bob_addr = bob_transport.local_addr();
+
+        alice_transport
+            .connect(&"alice".to_string(), "bob".to_string(), bob_addr)
+            .unwrap();
+        bob_transport
+            .connect(&"bob".to_string(), "alice".to_string(), alice_addr)
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+
+        for _ in 0..5 {
+            alice.resync_peer(&mut alice_transport, &"bob".to_string());
+            bob.resync_peer(&mut bob_transport, &"alice".to_string());
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let msg = alice
+            .add_message(ChatMessage::new("alice", "hi bob, just us now"))
+            .unwrap();
+        alice.send_message(&mut alice_transport, &"bob".to_string(), &msg);
+
+        thread::sleep(Duration::from_millis(100));
+        bob.poll_messages(&mut bob_transport);
+
+        assert_eq!(bob.get_messages().len(), 1);
+        assert_eq!(bob.get_messages(), alice.get_messages());
+    }
+}
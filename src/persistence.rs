@@ -0,0 +1,295 @@
+//! Persisting a `ChatUser`'s document across runs.
+//!
+//! There's no way today to close a chat and reopen it later with its
+//! history intact. This adds two ways to do that: a full `save`/`load`
+//! snapshot, and an incremental-append mode (`append_incremental` /
+//! `load_incremental_log`) so a long-running chat doesn't rewrite the whole
+//! document to disk on every message. `compact` replays a change log down
+//! to a single snapshot and atomically swaps it in, so disk usage stays
+//! bounded; the snapshot is written as just another tagged frame, so the
+//! file is still a log `append_incremental`/`load_incremental_log` can keep
+//! using afterward.
+
+use crate::ChatUser;
+use automerge::AutoCommit;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub(crate) enum PersistenceError {
+    Io(io::Error),
+    Automerge(automerge::AutomergeError),
+}
+
+impl From<io::Error> for PersistenceError {
+    fn from(err: io::Error) -> Self {
+        PersistenceError::Io(err)
+    }
+}
+
+impl From<automerge::AutomergeError> for PersistenceError {
+    fn from(err: automerge::AutomergeError) -> Self {
+        PersistenceError::Automerge(err)
+    }
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::Io(err) => write!(f, "io error: {err}"),
+            PersistenceError::Automerge(err) => write!(f, "automerge error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+/// Whether a logged frame holds an `append_incremental` diff or a `compact`
+/// snapshot. Tagging each frame (rather than assuming diffs throughout)
+/// lets a compacted file go on accepting more `append_incremental` calls
+/// without `load_incremental_log` mistaking the snapshot for a diff.
+#[derive(Clone, Copy)]
+enum FrameKind {
+    Diff,
+    Snapshot,
+}
+
+impl FrameKind {
+    fn tag(self) -> u8 {
+        match self {
+            FrameKind::Diff => 0,
+            FrameKind::Snapshot => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(FrameKind::Diff),
+            1 => Some(FrameKind::Snapshot),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a tag byte followed by a `u32`-length-prefixed frame (the same
+/// length-prefix framing `NetworkMessage` uses on the wire), so a log is
+/// just a sequence of these.
+fn write_frame(file: &mut File, kind: FrameKind, bytes: &[u8]) -> io::Result<()> {
+    file.write_all(&[kind.tag()])?;
+    file.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    file.write_all(bytes)
+}
+
+/// Reads one tagged frame starting at `*offset`, advancing it past the
+/// frame. Returns `None` once the log is exhausted or the next byte isn't a
+/// frame this code wrote.
+fn read_frame(bytes: &[u8], offset: &mut usize) -> Option<(FrameKind, Vec<u8>)> {
+    if *offset + 1 > bytes.len() {
+        return None;
+    }
+    let kind = FrameKind::from_tag(bytes[*offset])?;
+    *offset += 1;
+
+    if *offset + 4 > bytes.len() {
+        return None;
+    }
+    let len = u32::from_be_bytes(bytes[*offset..*offset + 4].try_into().unwrap()) as usize;
+    *offset += 4;
+    if *offset + len > bytes.len() {
+        return None;
+    }
+    let frame = bytes[*offset..*offset + len].to_vec();
+    *offset += len;
+    Some((kind, frame))
+}
+
+/// Replays every frame of a log into a fresh document: a `Diff` frame is
+/// folded in with `load_incremental`, a `Snapshot` frame (written by
+/// `compact`) replaces the document outright with `AutoCommit::load`.
+fn replay_incremental_log(bytes: &[u8]) -> Result<AutoCommit, PersistenceError> {
+    let mut doc = AutoCommit::new();
+    let mut offset = 0;
+    while let Some((kind, frame)) = read_frame(bytes, &mut offset) {
+        match kind {
+            FrameKind::Diff => {
+                doc.load_incremental(&frame)?;
+            }
+            FrameKind::Snapshot => {
+                doc = AutoCommit::load(&frame)?;
+            }
+        }
+    }
+    Ok(doc)
+}
+
+impl ChatUser {
+    /// Writes a full document snapshot to `path`, overwriting whatever was
+    /// there. Pairs with `load`.
+    pub(crate) fn save(&self, path: impl AsRef<Path>) -> Result<(), PersistenceError> {
+        fs::write(path, self.doc.save())?;
+        Ok(())
+    }
+
+    /// Reconstructs a `ChatUser` from a snapshot written by `save`. The user
+    /// id isn't part of the document, so it's supplied separately.
+    pub(crate) fn load(user_id: &str, path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+        let bytes = fs::read(path)?;
+        let doc = AutoCommit::load(&bytes)?;
+        Ok(ChatUser {
+            id: user_id.to_string(),
+            doc,
+            peer_states: Default::default(),
+            unread_cache: Default::default(),
+        })
+    }
+
+    /// Appends only what's changed since the document was loaded (or since
+    /// the last `append_incremental` call) to `path` as one more frame,
+    /// instead of rewriting the whole document. A no-op if nothing changed.
+    pub(crate) fn append_incremental(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), PersistenceError> {
+        let diff = self.doc.save_incremental();
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        write_frame(&mut file, FrameKind::Diff, &diff)?;
+        Ok(())
+    }
+
+    /// Reconstructs a `ChatUser` by replaying an incremental change log
+    /// written by `append_incremental`.
+    pub(crate) fn load_incremental_log(
+        user_id: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, PersistenceError> {
+        let bytes = fs::read(path)?;
+        let doc = replay_incremental_log(&bytes)?;
+        Ok(ChatUser {
+            id: user_id.to_string(),
+            doc,
+            peer_states: Default::default(),
+            unread_cache: Default::default(),
+        })
+    }
+
+    /// Replays `path`'s change log into a fresh document and atomically
+    /// replaces it with a single compacted snapshot frame, so a
+    /// long-running chat's disk usage doesn't grow without bound. The
+    /// snapshot is written in the same tagged-frame format `append_incremental`
+    /// uses, so the file is still a valid log afterward: it can keep
+    /// growing via `append_incremental` and be replayed with
+    /// `load_incremental_log`, not just reopened with `load`.
+    pub(crate) fn compact(path: impl AsRef<Path>) -> Result<(), PersistenceError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let doc = replay_incremental_log(&bytes)?;
+
+        let tmp_path = path.with_extension("compact.tmp");
+        let mut tmp_file = File::create(&tmp_path)?;
+        write_frame(&mut tmp_file, FrameKind::Snapshot, &doc.save())?;
+        drop(tmp_file);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatMessage;
+    use uuid::Uuid;
+
+    /// A path under the system temp dir unique to this test run, so
+    /// parallel test threads can't clobber each other's files.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("automerge-experiments-{name}-{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let mut user = ChatUser::new("user1").unwrap();
+        user.add_message(ChatMessage::new("user1", "hello"))
+            .unwrap();
+
+        let path = temp_path("save-load");
+        user.save(&path).unwrap();
+
+        let loaded = ChatUser::load("user1", &path).unwrap();
+        assert_eq!(loaded.get_messages(), user.get_messages());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_incremental_round_trip() {
+        let mut user = ChatUser::new("user1").unwrap();
+        let path = temp_path("append");
+
+        user.add_message(ChatMessage::new("user1", "first"))
+            .unwrap();
+        user.append_incremental(&path).unwrap();
+
+        user.add_message(ChatMessage::new("user1", "second"))
+            .unwrap();
+        user.append_incremental(&path).unwrap();
+
+        let loaded = ChatUser::load_incremental_log("user1", &path).unwrap();
+        assert_eq!(loaded.get_messages().len(), 2);
+        assert_eq!(loaded.get_messages(), user.get_messages());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_incremental_is_a_no_op_without_changes() {
+        let mut user = ChatUser::new("user1").unwrap();
+        let path = temp_path("append-noop");
+
+        user.add_message(ChatMessage::new("user1", "first"))
+            .unwrap();
+        user.append_incremental(&path).unwrap();
+        let after_first_append = fs::read(&path).unwrap();
+
+        // Nothing changed since the last append, so this one shouldn't
+        // touch the file at all.
+        user.append_incremental(&path).unwrap();
+        let after_second_append = fs::read(&path).unwrap();
+
+        assert_eq!(after_first_append, after_second_append);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compact_preserves_content_and_stays_appendable() {
+        let mut user = ChatUser::new("user1").unwrap();
+        let path = temp_path("compact");
+
+        user.add_message(ChatMessage::new("user1", "first"))
+            .unwrap();
+        user.append_incremental(&path).unwrap();
+
+        ChatUser::compact(&path).unwrap();
+
+        let mut reloaded = ChatUser::load_incremental_log("user1", &path).unwrap();
+        assert_eq!(reloaded.get_messages().len(), 1);
+
+        // The file must still be a valid log after compaction: further
+        // appends and reloads should keep working, not just a plain `load`.
+        reloaded
+            .add_message(ChatMessage::new("user1", "second"))
+            .unwrap();
+        reloaded.append_incremental(&path).unwrap();
+
+        let final_user = ChatUser::load_incremental_log("user1", &path).unwrap();
+        assert_eq!(final_user.get_messages().len(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+//! Randomized multi-peer sync simulation used to fuzz for convergence bugs.
+//!
+//! `main` only ever exercises `generate_sync_message`/`receive_sync_message`
+//! in a fixed two-peer loop. This module drives an arbitrary number of
+//! `AutoCommit` documents through randomized, reordered, and duplicated
+//! message delivery and asserts that every replica ends up with the
+//! identical message log.
+
+use crate::{add_message_to_doc, genesis_doc_bytes, read_messages_from_doc, ChatMessage};
+use automerge::sync::{self, SyncDoc};
+use automerge::{AutoCommit, Change};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+pub(crate) type NodeId = usize;
+
+/// Probability that `deliver_random_envelope` redelivers the envelope it
+/// just popped, simulating a transport that duplicates a message instead of
+/// losing or reordering it.
+const DUPLICATE_DELIVERY_PROBABILITY: f64 = 0.1;
+
+/// Probability that a local edit is also gossiped to a random peer as a raw
+/// change, ahead of (or instead of) the formal sync handshake.
+const GOSSIP_CHANGE_PROBABILITY: f64 = 0.2;
+
+/// A change or sync message in flight between two simulated peers. Both
+/// variants are safe to deliver more than once: automerge dedups changes by
+/// hash, and a sync message only ever drives `State` forward.
+#[derive(Clone)]
+pub(crate) enum EnvelopeBody {
+    Sync(Vec<u8>),
+    Change(Vec<u8>),
+}
+
+pub(crate) struct Envelope {
+    from: NodeId,
+    to: NodeId,
+    body: EnvelopeBody,
+}
+
+/// A node's view of every other node's sync progress; `Simulation` keeps it
+/// next to the node's document. Seeding every other node id in here up
+/// front lets a node's behaviour discover its peers just by iterating this
+/// map.
+type PeerStates = HashMap<NodeId, sync::State>;
+
+/// Decides what a node does when it makes a local edit and when it receives
+/// an envelope from another node. Kept as a trait so the simulation driver
+/// doesn't need to know anything about chat semantics.
+pub(crate) trait NodeBehaviour {
+    fn on_change(&mut self, node: NodeId, doc: &mut AutoCommit, peers: &mut PeerStates) -> Vec<Envelope>;
+
+    fn on_receive(
+        &mut self,
+        node: NodeId,
+        doc: &mut AutoCommit,
+        peers: &mut PeerStates,
+        from: NodeId,
+    ) -> Vec<Envelope>;
+}
+
+/// The behaviour used by the chat simulation: a local edit (or an inbound
+/// envelope) always tries to push a fresh sync message to every known peer,
+/// using the same generate/receive sync exchange `sync_until_converged`
+/// runs between two documents.
+struct ChatBehaviour;
+
+impl ChatBehaviour {
+    fn sync_with_every_peer(
+        &self,
+        node: NodeId,
+        doc: &mut AutoCommit,
+        peers: &mut PeerStates,
+    ) -> Vec<Envelope> {
+        let mut envelopes = Vec::new();
+        for (&peer, state) in peers.iter_mut() {
+            if let Some(message) = doc.sync().generate_sync_message(state) {
+                envelopes.push(Envelope {
+                    from: node,
+                    to: peer,
+                    body: EnvelopeBody::Sync(message.encode()),
+                });
+            }
+        }
+        envelopes
+    }
+}
+
+impl NodeBehaviour for ChatBehaviour {
+    fn on_change(&mut self, node: NodeId, doc: &mut AutoCommit, peers: &mut PeerStates) -> Vec<Envelope> {
+        self.sync_with_every_peer(node, doc, peers)
+    }
+
+    fn on_receive(
+        &mut self,
+        node: NodeId,
+        doc: &mut AutoCommit,
+        peers: &mut PeerStates,
+        from: NodeId,
+    ) -> Vec<Envelope> {
+        // For a sync envelope, the driver already folded it into
+        // `peers[from]` before this is called (a raw change envelope is
+        // applied to `doc` only); just try to keep pushing sync progress
+        // forward.
+        let _ = from;
+        self.sync_with_every_peer(node, doc, peers)
+    }
+}
+
+struct Simulation {
+    nodes: HashMap<NodeId, (AutoCommit, PeerStates)>,
+    queue: Vec<Envelope>,
+    rng: SmallRng,
+    behaviour: ChatBehaviour,
+}
+
+impl Simulation {
+    fn new(seed: u64, node_count: usize) -> Self {
+        // Every node forks from the same genesis bytes (see
+        // `genesis_doc_bytes`) instead of an independent `AutoCommit::new`,
+        // so the shared `"messages"`/`"read_markers"` containers already
+        // exist before any node can race to create its own copy of one.
+        let genesis = genesis_doc_bytes();
+        let ids: Vec<NodeId> = (0..node_count).collect();
+        let nodes = ids
+            .iter()
+            .map(|&id| {
+                let peers = ids
+                    .iter()
+                    .filter(|&&other| other != id)
+                    .map(|&other| (other, sync::State::new()))
+                    .collect();
+                let doc = AutoCommit::load(&genesis).expect("load genesis doc");
+                (id, (doc, peers))
+            })
+            .collect();
+
+        Simulation {
+            nodes,
+            queue: Vec::new(),
+            rng: SmallRng::seed_from_u64(seed),
+            behaviour: ChatBehaviour,
+        }
+    }
+
+    fn node_ids(&self) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Injects a random local edit on a random node.
+    fn inject_random_edit(&mut self) {
+        let ids = self.node_ids();
+        let node = ids[self.rng.gen_range(0..ids.len())];
+
+        let message = ChatMessage::new(&format!("node{node}"), "sim message");
+        let (doc, peers) = self.nodes.get_mut(&node).unwrap();
+        add_message_to_doc(doc, message).expect("apply random edit");
+
+        let mut envelopes = self.behaviour.on_change(node, doc, peers);
+
+        // Occasionally also gossip the raw change straight to one peer,
+        // exercising delivery that isn't mediated by the sync handshake at
+        // all (e.g. a peer relaying what it just heard about).
+        let peer_ids: Vec<NodeId> = peers.keys().copied().collect();
+        if !peer_ids.is_empty() && self.rng.gen_bool(GOSSIP_CHANGE_PROBABILITY) {
+            let to = peer_ids[self.rng.gen_range(0..peer_ids.len())];
+            let change = doc
+                .get_last_local_change()
+                .expect("just applied a local change")
+                .raw_bytes()
+                .to_vec();
+            envelopes.push(Envelope {
+                from: node,
+                to,
+                body: EnvelopeBody::Change(change),
+            });
+        }
+
+        self.queue.extend(envelopes);
+    }
+
+    /// Pops a random queued envelope (not necessarily the oldest one, so
+    /// delivery order is reordered/non-FIFO) and delivers it. With small
+    /// probability the envelope is also pushed back onto the queue
+    /// afterward, so the same sync message or change gets delivered twice.
+    fn deliver_random_envelope(&mut self) {
+        let index = self.rng.gen_range(0..self.queue.len());
+        let envelope = self.queue.swap_remove(index);
+
+        if self.rng.gen_bool(DUPLICATE_DELIVERY_PROBABILITY) {
+            self.queue.push(Envelope {
+                from: envelope.from,
+                to: envelope.to,
+                body: envelope.body.clone(),
+            });
+        }
+
+        let (doc, peers) = self.nodes.get_mut(&envelope.to).unwrap();
+        match envelope.body {
+            EnvelopeBody::Sync(bytes) => {
+                let message = sync::Message::decode(&bytes).expect("decode sync message");
+                let state = peers.entry(envelope.from).or_insert_with(sync::State::new);
+                doc.sync()
+                    .receive_sync_message(state, message)
+                    .expect("receive sync message");
+            }
+            EnvelopeBody::Change(bytes) => {
+                let change = Change::from_bytes(bytes).expect("decode change");
+                doc.apply_changes(vec![change]).expect("apply change");
+            }
+        }
+
+        let response = self.behaviour.on_receive(envelope.to, doc, peers, envelope.from);
+        self.queue.extend(response);
+    }
+
+    /// Keeps delivering whatever is left in the queue until it drains and a
+    /// full pass produces no further envelopes, i.e. every node agrees it
+    /// has nothing left to say to any peer.
+    fn drain_until_quiescent(&mut self) {
+        loop {
+            if self.queue.is_empty() {
+                let ids = self.node_ids();
+                let mut produced_more = false;
+                for node in ids {
+                    let (doc, peers) = self.nodes.get_mut(&node).unwrap();
+                    let envelopes = self.behaviour.on_change(node, doc, peers);
+                    produced_more |= !envelopes.is_empty();
+                    self.queue.extend(envelopes);
+                }
+                if !produced_more {
+                    break;
+                }
+            } else {
+                self.deliver_random_envelope();
+            }
+        }
+    }
+
+    fn assert_converged(&self) {
+        let ids = self.node_ids();
+        let first = read_messages_from_doc(&self.nodes[&ids[0]].0);
+        for &id in &ids[1..] {
+            let messages = read_messages_from_doc(&self.nodes[&id].0);
+            assert_eq!(
+                first, messages,
+                "node {} diverged from node {}",
+                id, ids[0]
+            );
+        }
+    }
+}
+
+/// Runs a randomized convergence simulation with three nodes for `steps`
+/// random steps, drains any remaining in-flight sync traffic, then
+/// asserts every node's message log is identical.
+///
+/// Meant to be wrapped in a `proptest!` over `seed` so failing seeds shrink.
+pub(crate) fn run(seed: u64, steps: usize) {
+    run_with_nodes(seed, steps, 3)
+}
+
+fn run_with_nodes(seed: u64, steps: usize, node_count: usize) {
+    let mut sim = Simulation::new(seed, node_count);
+
+    for _ in 0..steps {
+        if sim.queue.is_empty() || sim.rng.gen_bool(0.5) {
+            sim.inject_random_edit();
+        } else {
+            sim.deliver_random_envelope();
+        }
+    }
+
+    sim.drain_until_quiescent();
+    sim.assert_converged();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn converges_for_any_seed(seed: u64, steps in 1usize..200) {
+            run(seed, steps);
+        }
+    }
+}